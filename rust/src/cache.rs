@@ -23,19 +23,24 @@
 //! This is an implementation of a LRU Cache that evicts objects
 //! based on the total size of the cached objects.
 //!
+//! Internally the cache is split into independently-locked shards
+//! (`LruCache::with_shards`) so concurrent `get`/`put` calls that land in
+//! different shards don't serialize behind a single mutex.
+//!
 //! # Examples
 //!
 //! ```
-//! let cache: LruCache<u32, u32> = LruCache::new(200);
+//! // pin to a single shard so eviction order is deterministic
+//! let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
 //!
-//! cache.put(0, 0, 100);
-//! cache.put(1, 1, 100);
+//! cache.put(0, 0, 100, Priority::Low);
+//! cache.put(1, 1, 100, Priority::Low);
 //!
 //! // Accessing the first entry brings it to the top the LRU
 //! cache.get(&0);
 //!
 //! // this will push the least-recently-used entry out of the cache
-//! cache.put(2, 2, 100);
+//! cache.put(2, 2, 100, Priority::Low);
 //!
 //! // second entry should be evicted from cache
 //! assert!(cache.get(&1).is_none());
@@ -45,15 +50,222 @@
 //! assert_eq!(cache.get(&2), Some(Arc::new(2)));
 //! ```
 
+extern crate flate2;
 extern crate linked_hash_map;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use linked_hash_map::LinkedHashMap;
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
+/// Eviction policy used by an LruCache shard.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Strict least-recently-used eviction (the default). Every `get`
+    /// moves the entry to the back of the shard's queue.
+    Lru,
+    /// CLOCK (second-chance) eviction. `get` only sets the entry's
+    /// referenced bit; eviction sweeps from the front and gives
+    /// referenced entries a second chance instead of evicting them
+    /// outright.
+    Clock,
+}
+
+/// Relative importance of a cached entry, used to decide eviction order.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Evicted before any high-priority entry while the shard's
+    /// high-priority pool is within its budget. The default priority used
+    /// by `LruCache::put`.
+    Low,
+    /// Protected from eviction as long as the shard's high-priority pool
+    /// doesn't exceed its budget; once it does, the oldest high-priority
+    /// entry is demoted and evicted like any other.
+    High,
+}
+
+/// Types whose cached value can be losslessly round-tripped through a
+/// byte buffer, so they can be compressed into a secondary cache tier
+/// once evicted from the primary tier.
+pub trait Compressible: Sized {
+    /// Serialize this value to the bytes that will be compressed.
+    fn to_bytes(&self) -> Vec<u8>;
+    /// Reconstruct a value from previously-serialized, decompressed bytes.
+    fn from_bytes(bytes: Vec<u8>) -> Self;
+}
+
+impl Compressible for u32 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(&bytes[..4]);
+        u32::from_le_bytes(buf)
+    }
+}
+
+impl Compressible for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone().into_bytes()
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        String::from_utf8(bytes).expect("secondary cache tier contains corrupt UTF-8 data")
+    }
+}
+
+// Byte-codec function pair used to compress/decompress a value for the
+// secondary tier, set only when one is configured.
+type Codec<V> = (fn(&V) -> Vec<u8>, fn(Vec<u8>) -> V);
+
+/// Types that can estimate their own memory footprint, used by
+/// `LruCache::put_sized` to derive a cache entry's weight instead of
+/// requiring the caller to hand-compute it.
+pub trait MemSize {
+    fn mem_size(&self) -> usize;
+}
+
+macro_rules! impl_mem_size_as_size_of {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl MemSize for $t {
+                fn mem_size(&self) -> usize {
+                    std::mem::size_of::<Self>()
+                }
+            }
+        )*
+    };
+}
+
+impl_mem_size_as_size_of!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char,
+);
+
+impl MemSize for String {
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.capacity()
+    }
+}
+
+impl<T: MemSize> MemSize for Vec<T> {
+    fn mem_size(&self) -> usize {
+        // stack size, plus the (possibly unused) heap buffer, plus
+        // whatever each element itself owns beyond its own stack slot
+        std::mem::size_of::<Self>()
+            + self.capacity() * std::mem::size_of::<T>()
+            + self
+                .iter()
+                .map(|item| item.mem_size().saturating_sub(std::mem::size_of::<T>()))
+                .sum::<usize>()
+    }
+}
+
+impl<T: MemSize> MemSize for Option<T> {
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self
+                .as_ref()
+                .map(|v| v.mem_size().saturating_sub(std::mem::size_of::<T>()))
+                .unwrap_or(0)
+    }
+}
+
+impl<T: MemSize> MemSize for Box<T> {
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + (**self).mem_size()
+    }
+}
+
+// Compress `bytes` for storage in a secondary tier.
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .expect("in-memory zlib compression cannot fail");
+    encoder
+        .finish()
+        .expect("in-memory zlib compression cannot fail")
+}
+
+// Decompress bytes previously produced by `compress`.
+fn decompress(bytes: &[u8]) -> Vec<u8> {
+    let mut decoder = ZlibDecoder::new(bytes);
+    let mut out = Vec::new();
+    decoder
+        .read_to_end(&mut out)
+        .expect("secondary cache tier contains corrupt compressed data");
+    out
+}
+
 // Used to hold references to cached entries and their size/weight
 struct CacheItem<V> {
     entry: Arc<V>,
     size: usize,
+    // set by `get` under the Clock policy; cleared (and given a second
+    // chance) the first time the eviction sweep passes over it
+    referenced: AtomicBool,
+    priority: Priority,
+}
+
+// A compressed entry that was evicted from the primary tier.
+struct SecondaryItem {
+    data: Vec<u8>,
+    // uncompressed size, i.e. the weight the entry had in the primary tier
+    original_size: usize,
+}
+
+// The secondary (compressed) tier that evicted primary entries spill into.
+struct SecondaryTier<K> {
+    lru: LinkedHashMap<K, SecondaryItem>,
+    capacity: usize,
+    total_size: usize,
+}
+
+impl<K> SecondaryTier<K>
+where
+    K: Hash + Eq,
+{
+    fn insert(&mut self, key: K, data: Vec<u8>, original_size: usize) {
+        if let Some(old) = self.lru.remove(&key) {
+            self.total_size -= old.data.len();
+        }
+
+        let size = data.len();
+        while self.total_size + size > self.capacity {
+            match self.lru.pop_front() {
+                Some((_, old)) => self.total_size -= old.data.len(),
+                None => break,
+            }
+        }
+
+        self.lru.insert(key, SecondaryItem { data, original_size });
+        self.total_size += size;
+    }
+
+    // Remove and return the compressed entry for `key`, if present.
+    fn take(&mut self, key: &K) -> Option<(Vec<u8>, usize)> {
+        let item = self.lru.remove(key)?;
+        self.total_size -= item.data.len();
+        Some((item.data, item.original_size))
+    }
+}
+
+// Running hit/miss/eviction counters for a single shard. `current_size`
+// and `capacity` aren't duplicated here since `_LruCache` already tracks
+// them authoritatively; `stats()` reads those directly.
+#[derive(Default)]
+struct Counters {
+    lookups: usize,
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+    peak_size: usize,
 }
 
 // Cache stuff that can only be accessed while a `std::sync::Mutex` is held.
@@ -61,55 +273,286 @@ struct _LruCache<K, V> {
     lru: LinkedHashMap<K, CacheItem<V>>,
     capacity: usize,
     total_size: usize,
+    policy: EvictionPolicy,
+    secondary: Option<SecondaryTier<K>>,
+    // bytes<->value codec for the secondary tier, only set when one is
+    // configured (see `LruCache::with_secondary_options`).
+    codec: Option<Codec<V>>,
+    // number of entries reconstructed from the secondary tier
+    promotions: usize,
+    counters: Counters,
+    // budget for, and current occupancy of, the high-priority pool
+    high_priority_capacity: usize,
+    high_priority_size: usize,
+    // ratio `high_priority_capacity` was last derived from, kept so
+    // `set_capacity` can re-derive it instead of letting it drift
+    high_priority_ratio: f64,
 }
 
 impl<K, V> _LruCache<K, V>
 where
-    K: Hash + Eq,
+    K: Hash + Eq + Clone,
 {
-    // Drop entries to clear enough cache space to add `reserve` bytes.
+    // Drop entries to clear enough cache space to add `reserve` bytes,
+    // demoting evicted entries into the secondary tier when one exists.
+    //
+    // High-priority entries are skipped (given back to the queue) as long
+    // as the high-priority pool doesn't exceed its budget; once it does, the
+    // oldest high-priority entry is demoted to low priority and evicted
+    // instead.
     fn _shrink_to_fit(&mut self, reserve: usize) {
         while self.total_size + reserve > self.capacity {
-            match self.lru.pop_front() {
-                Some(val) => {
-                    self.total_size -= val.1.size;
+            // bound the scan to one pass over the shard so an all-protected
+            // queue can't spin forever instead of giving up like the
+            // `None` case below
+            let scan_limit = self.lru.len();
+            let mut evicted = None;
+            for _ in 0..scan_limit {
+                let candidate = match self.policy {
+                    EvictionPolicy::Lru => self.lru.pop_front(),
+                    EvictionPolicy::Clock => loop {
+                        match self.lru.pop_front() {
+                            // referenced: give it a second chance, clear the
+                            // bit and move it to the back of the queue
+                            Some((key, item)) if item.referenced.swap(false, Ordering::Relaxed) => {
+                                self.lru.insert(key, item);
+                            }
+                            other => break other,
+                        }
+                    },
+                };
+                let (key, mut item) = match candidate {
+                    Some(kv) => kv,
+                    None => break,
+                };
+                if item.priority == Priority::High
+                    && self.high_priority_size <= self.high_priority_capacity
+                {
+                    self.lru.insert(key, item);
+                    continue;
+                }
+                if item.priority == Priority::High {
+                    self.high_priority_size -= item.size;
+                    item.priority = Priority::Low;
+                }
+                evicted = Some((key, item));
+                break;
+            }
+            match evicted {
+                Some((key, item)) => {
+                    self.total_size -= item.size;
+                    self.counters.evictions += 1;
+                    if let (Some(secondary), Some((to_bytes, _))) =
+                        (self.secondary.as_mut(), self.codec)
+                    {
+                        let compressed = compress(&to_bytes(&item.entry));
+                        secondary.insert(key, compressed, item.size);
+                    }
                 }
                 None => break,
             }
         }
     }
+
+    // Record that `total_size` grew, updating the high-water mark.
+    fn _note_grew(&mut self) {
+        self.counters.peak_size = self.counters.peak_size.max(self.total_size);
+    }
+}
+
+/// Default number of shards used by `LruCache::new`.
+const DEFAULT_SHARDS: usize = 8;
+
+/// Cache effectiveness counters, aggregated across all shards by
+/// `LruCache::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Total number of `get` calls.
+    pub lookups: usize,
+    /// `get` calls that found the key, in either tier.
+    pub hits: usize,
+    /// `get` calls that didn't find the key in any tier.
+    pub misses: usize,
+    /// Entries dropped out of the primary tier to make room.
+    pub evictions: usize,
+    /// Bytes currently resident in the primary tier.
+    pub current_size: usize,
+    /// Sum of each shard's high-water mark of resident bytes; with more
+    /// than one shard this can exceed the highest `current_size` reached.
+    pub peak_size: usize,
+    /// Configured primary tier capacity, in bytes.
+    pub capacity: usize,
+}
+
+/// Configuration knobs for constructing an LruCache. Defaults to
+/// `DEFAULT_SHARDS` shards, LRU eviction, and no secondary tier.
+#[derive(Clone, Copy)]
+pub struct CacheOptions {
+    pub shards: usize,
+    pub policy: EvictionPolicy,
+    /// Total size budget, across all shards, for the compressed secondary
+    /// tier that evicted primary entries are demoted into. `0` disables
+    /// the secondary tier; it only takes effect via `with_secondary`/
+    /// `with_secondary_options`, since building one needs a `V:
+    /// Compressible` codec.
+    pub secondary_capacity_in_bytes: usize,
+    /// Fraction (`0.0..=1.0`) of each shard's capacity reserved for
+    /// high-priority entries. `0.0` (the default) preserves plain
+    /// LRU/CLOCK behavior.
+    pub high_priority_ratio: f64,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            shards: DEFAULT_SHARDS,
+            policy: EvictionPolicy::Lru,
+            secondary_capacity_in_bytes: 0,
+            high_priority_ratio: 0.0,
+        }
+    }
 }
 
 /// LRU cache implementation.
-pub struct LruCache<K, V>(Mutex<_LruCache<K, V>>);
+///
+/// The cache is partitioned into a number of shards, each guarded by its
+/// own mutex, so that `(plane, x, y)` accesses that hash to different
+/// shards don't block each other.
+pub struct LruCache<K, V> {
+    shards: Vec<Mutex<_LruCache<K, V>>>,
+}
 
 impl<K, V> LruCache<K, V>
 where
-    K: Hash + Eq,
+    K: Hash + Eq + Clone,
 {
     /// Initialize a new LruCache, with the specified maximum size.
+    ///
+    /// Uses `DEFAULT_SHARDS` shards and strict LRU eviction with no
+    /// secondary tier; see `with_options`/`with_secondary` to customize.
     pub fn new(capacity_in_bytes: usize) -> LruCache<K, V> {
-        LruCache(Mutex::new(_LruCache {
-            lru: LinkedHashMap::new(),
-            capacity: capacity_in_bytes,
-            total_size: 0,
-        }))
+        LruCache::with_options(capacity_in_bytes, CacheOptions::default())
+    }
+
+    /// Initialize a new LruCache with an explicit number of shards.
+    ///
+    /// `shards` is rounded up to the next power of two (minimum 1); the
+    /// capacity is divided evenly across them.
+    pub fn with_shards(capacity_in_bytes: usize, shards: usize) -> LruCache<K, V> {
+        LruCache::with_options(
+            capacity_in_bytes,
+            CacheOptions {
+                shards,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Initialize a new LruCache with an explicit eviction policy, using
+    /// `DEFAULT_SHARDS` shards.
+    pub fn with_policy(capacity_in_bytes: usize, policy: EvictionPolicy) -> LruCache<K, V> {
+        LruCache::with_options(
+            capacity_in_bytes,
+            CacheOptions {
+                policy,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Initialize a new LruCache with an explicit number of shards and
+    /// eviction policy. See `with_shards` for the shard count semantics.
+    pub fn with_shards_and_policy(
+        capacity_in_bytes: usize,
+        shards: usize,
+        policy: EvictionPolicy,
+    ) -> LruCache<K, V> {
+        LruCache::with_options(
+            capacity_in_bytes,
+            CacheOptions {
+                shards,
+                policy,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Initialize a new LruCache with the given CacheOptions, without a
+    /// secondary tier. Works for any `V`; `options.secondary_capacity_in_bytes`
+    /// is ignored here since a working secondary tier needs a `V:
+    /// Compressible` codec (see `with_secondary_options`).
+    pub fn with_options(capacity_in_bytes: usize, options: CacheOptions) -> LruCache<K, V> {
+        Self::_new(capacity_in_bytes, options, None)
+    }
+
+    // Shared by `with_options` (no codec, so the secondary tier is never
+    // actually built) and `with_secondary`/`with_secondary_options` (codec
+    // derived from `V: Compressible`).
+    fn _new(
+        capacity_in_bytes: usize,
+        options: CacheOptions,
+        codec: Option<Codec<V>>,
+    ) -> LruCache<K, V> {
+        let num_shards = options.shards.max(1).next_power_of_two();
+        let per_shard_capacity = capacity_in_bytes / num_shards;
+        let per_shard_secondary_capacity = options.secondary_capacity_in_bytes / num_shards;
+        let per_shard_high_priority_capacity =
+            (per_shard_capacity as f64 * options.high_priority_ratio) as usize;
+
+        let shards = (0..num_shards)
+            .map(|_| {
+                Mutex::new(_LruCache {
+                    lru: LinkedHashMap::new(),
+                    capacity: per_shard_capacity,
+                    total_size: 0,
+                    policy: options.policy,
+                    secondary: if codec.is_some() && options.secondary_capacity_in_bytes > 0 {
+                        Some(SecondaryTier {
+                            lru: LinkedHashMap::new(),
+                            capacity: per_shard_secondary_capacity,
+                            total_size: 0,
+                        })
+                    } else {
+                        None
+                    },
+                    codec,
+                    promotions: 0,
+                    counters: Counters::default(),
+                    high_priority_capacity: per_shard_high_priority_capacity,
+                    high_priority_size: 0,
+                    high_priority_ratio: options.high_priority_ratio,
+                })
+            })
+            .collect();
+
+        LruCache { shards }
+    }
+
+    // Pick the shard responsible for `key`.
+    fn shard_for(&self, key: &K) -> &Mutex<_LruCache<K, V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let index = (hasher.finish() as usize) & (self.shards.len() - 1);
+        &self.shards[index]
     }
 
     /// Get configured LruCache maximum size
     ///
-    /// **Note to self:** Maybe it would be more useful to return
-    /// the total size of currently cached objects?
+    /// This is the sum of the (evenly divided) per-shard capacities, which
+    /// may be a few bytes below the originally requested size due to
+    /// rounding.
     pub fn get_capacity(&self) -> usize {
-        let cache = self.0.lock().unwrap();
-
-        cache.capacity
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().capacity)
+            .sum()
     }
 
     /// Set new LruCache maximum capacity
     ///
-    /// Will discard least recently used objects that exceed the new
-    /// size, can as such be used to empty the current cache.
+    /// The new capacity is divided evenly across the existing shards,
+    /// each of which discards its least recently used objects that
+    /// exceed its new share; setting capacity to 0 empties the cache.
     ///
     /// ```
     /// let saved = cache.get_capacity();
@@ -117,28 +560,90 @@ where
     /// cache.set_capacity(saved);
     /// ```
     pub fn set_capacity(&self, capacity_in_bytes: usize) {
-        let mut cache = self.0.lock().unwrap();
+        let per_shard_capacity = capacity_in_bytes / self.shards.len();
+        for shard in &self.shards {
+            let mut cache = shard.lock().unwrap();
+            cache.capacity = per_shard_capacity;
+            // re-derive from the configured ratio so the high-priority
+            // pool's fraction of capacity doesn't silently drift
+            cache.high_priority_capacity =
+                (per_shard_capacity as f64 * cache.high_priority_ratio) as usize;
+            cache._shrink_to_fit(0); // resize shard to fit new size
+        }
+    }
+
+    /// Set the fraction of each shard's capacity reserved for high-priority
+    /// entries. Lowering the ratio doesn't evict anything by itself; an
+    /// over-budget high-priority pool is drained lazily, one demotion per
+    /// eviction, the next time a shard needs to make room.
+    pub fn set_high_priority_ratio(&self, ratio: f64) {
+        for shard in &self.shards {
+            let mut cache = shard.lock().unwrap();
+            cache.high_priority_ratio = ratio;
+            cache.high_priority_capacity = (cache.capacity as f64 * ratio) as usize;
+        }
+    }
+
+    /// Total number of entries reconstructed from the secondary tier
+    /// across all shards.
+    pub fn promotions(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().promotions)
+            .sum()
+    }
+
+    /// Snapshot of hit/miss/eviction counters, aggregated across all
+    /// shards, alongside the current and configured size of the primary
+    /// tier.
+    pub fn stats(&self) -> CacheStats {
+        self.shards.iter().fold(CacheStats::default(), |acc, shard| {
+            let cache = shard.lock().unwrap();
+            CacheStats {
+                lookups: acc.lookups + cache.counters.lookups,
+                hits: acc.hits + cache.counters.hits,
+                misses: acc.misses + cache.counters.misses,
+                evictions: acc.evictions + cache.counters.evictions,
+                current_size: acc.current_size + cache.total_size,
+                peak_size: acc.peak_size + cache.counters.peak_size,
+                capacity: acc.capacity + cache.capacity,
+            }
+        })
+    }
 
-        cache.capacity = capacity_in_bytes;
-        cache._shrink_to_fit(0); // resize cache to fit new size
+    /// Reset the lookup/hit/miss/eviction counters (including the peak
+    /// size high-water mark) back to zero.
+    pub fn reset_stats(&self) {
+        for shard in &self.shards {
+            let mut cache = shard.lock().unwrap();
+            cache.counters = Counters::default();
+        }
     }
 
     /// Add a new object to the cache.
     ///
     /// If the key already exists the existing entry is replaced.
-    /// Otherwise if the cache is full the least-recently-used
-    /// cached objects are discarded before the new object is added.
+    /// Otherwise if the key's shard is full the least-recently-used
+    /// cached objects in that shard are discarded (and, if a secondary
+    /// tier is configured, compressed into it) before the new object is
+    /// added.
     ///
     /// This function returns a reference to the newly added object.
-    pub fn put(&self, key: K, val: V, size: usize) -> Arc<V> {
-        let mut cache = self.0.lock().unwrap();
+    pub fn put(&self, key: K, val: V, size: usize, priority: Priority) -> Arc<V> {
+        let mut cache = self.shard_for(&key).lock().unwrap();
 
-        // remove key if it exists
+        // remove key if it exists, in either tier
         if let Some(old_val) = cache.lru.remove(&key) {
             cache.total_size -= old_val.size;
+            if old_val.priority == Priority::High {
+                cache.high_priority_size -= old_val.size;
+            }
+        }
+        if let Some(secondary) = cache.secondary.as_mut() {
+            secondary.take(&key);
         }
 
-        // drop entries to clear cache space
+        // drop entries to clear shard space
         cache._shrink_to_fit(size);
 
         // add the new entry
@@ -148,21 +653,123 @@ where
             CacheItem {
                 entry: val.clone(),
                 size,
+                referenced: AtomicBool::new(false),
+                priority,
             },
         );
         cache.total_size += size;
+        if priority == Priority::High {
+            cache.high_priority_size += size;
+        }
+        cache._note_grew();
         val
     }
 
+    /// Add a new object to the cache, deriving its weight from
+    /// `MemSize::mem_size` (plus the node's own bookkeeping overhead)
+    /// instead of requiring the caller to hand-compute `size`. Inserted at
+    /// `Priority::Low`; use `put` directly if you need to pin it.
+    pub fn put_sized(&self, key: K, val: V) -> Arc<V>
+    where
+        V: MemSize,
+    {
+        let size =
+            val.mem_size() + std::mem::size_of::<K>() + std::mem::size_of::<CacheItem<V>>();
+        self.put(key, val, size, Priority::Low)
+    }
+
     /// Retrieve a cached object.
     ///
-    /// If the key does not exist this function returns None.
-    /// Otherwise it returns a reference to the cached object.
+    /// A hit in the secondary (compressed) tier is transparently
+    /// decompressed and promoted back into the primary tier. Under the
+    /// LRU policy a primary hit moves the entry to the back of its
+    /// shard's queue; under CLOCK it just marks the entry as referenced.
     pub fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut cache = self.0.lock().unwrap();
+        let mut cache = self.shard_for(key).lock().unwrap();
+        cache.counters.lookups += 1;
+
+        let hit = match cache.policy {
+            EvictionPolicy::Lru => cache.lru.get_refresh(key).map(|item| item.entry.clone()),
+            EvictionPolicy::Clock => cache.lru.get(key).map(|item| {
+                item.referenced.store(true, Ordering::Relaxed);
+                item.entry.clone()
+            }),
+        };
+        if hit.is_some() {
+            cache.counters.hits += 1;
+            return hit;
+        }
+
+        // miss: see if the secondary (compressed) tier has it
+        let (compressed, original_size) = match cache.secondary.as_mut().and_then(|s| s.take(key))
+        {
+            Some(found) => found,
+            None => {
+                cache.counters.misses += 1;
+                return None;
+            }
+        };
+        cache.counters.hits += 1;
+        // a populated secondary tier always has a codec set alongside it
+        // (see `_new`)
+        let (_, from_bytes) = cache
+            .codec
+            .expect("secondary tier entry found without a codec configured");
+        let val = Arc::new(from_bytes(decompress(&compressed)));
+
+        cache._shrink_to_fit(original_size);
+        cache.lru.insert(
+            key.clone(),
+            CacheItem {
+                entry: val.clone(),
+                size: original_size,
+                referenced: AtomicBool::new(false),
+                // the secondary tier doesn't track priority; promoted
+                // entries come back in cold, at low priority
+                priority: Priority::Low,
+            },
+        );
+        cache.total_size += original_size;
+        cache._note_grew();
+        cache.promotions += 1;
 
-        let val = cache.lru.get_refresh(key)?;
-        Some(val.entry.clone())
+        Some(val)
+    }
+}
+
+impl<K, V> LruCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Compressible,
+{
+    /// Initialize a new LruCache with a compressed secondary tier of
+    /// `secondary_capacity_in_bytes` bytes that evicted primary entries
+    /// are demoted into instead of being dropped.
+    pub fn with_secondary(
+        capacity_in_bytes: usize,
+        secondary_capacity_in_bytes: usize,
+    ) -> LruCache<K, V> {
+        LruCache::with_secondary_options(
+            capacity_in_bytes,
+            CacheOptions {
+                secondary_capacity_in_bytes,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Initialize a new LruCache with the given CacheOptions, honoring
+    /// `options.secondary_capacity_in_bytes` (unlike `with_options`) by
+    /// deriving the compression codec from `V`'s Compressible impl.
+    pub fn with_secondary_options(
+        capacity_in_bytes: usize,
+        options: CacheOptions,
+    ) -> LruCache<K, V> {
+        Self::_new(
+            capacity_in_bytes,
+            options,
+            Some((V::to_bytes, V::from_bytes)),
+        )
     }
 }
 
@@ -172,10 +779,11 @@ mod tests {
 
     #[test]
     fn test_cache_capacity() {
-        let cache: LruCache<u32, u32> = LruCache::new(100);
+        // single shard so the full capacity evicts deterministically
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
         assert_eq!(cache.get_capacity(), 100);
 
-        cache.put(0, 0, 100);
+        cache.put(0, 0, 100, Priority::Low);
 
         cache.set_capacity(0);
         assert_eq!(cache.get_capacity(), 0);
@@ -186,7 +794,7 @@ mod tests {
 
     #[test]
     fn test_cache_empty() {
-        let cache: LruCache<u32, u32> = LruCache::new(100);
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
 
         // check key that was never inserted in cache
         assert!(cache.get(&0).is_none());
@@ -194,10 +802,10 @@ mod tests {
 
     #[test]
     fn test_cache_eviction() {
-        let cache: LruCache<u32, u32> = LruCache::new(100);
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
 
-        cache.put(0, 0, 100);
-        cache.put(1, 1, 100);
+        cache.put(0, 0, 100, Priority::Low);
+        cache.put(1, 1, 100, Priority::Low);
 
         // check if first entry was evicted from cache
         assert!(cache.get(&0).is_none());
@@ -208,16 +816,16 @@ mod tests {
 
     #[test]
     fn test_cache_lru() {
-        let cache: LruCache<u32, u32> = LruCache::new(200);
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
 
-        cache.put(0, 0, 100);
-        cache.put(1, 1, 100);
+        cache.put(0, 0, 100, Priority::Low);
+        cache.put(1, 1, 100, Priority::Low);
 
         // This should bring first entry to top
         assert_eq!(cache.get(&0), Some(Arc::new(0)));
 
         // this should now push the second entry out of the cache
-        cache.put(2, 2, 100);
+        cache.put(2, 2, 100, Priority::Low);
 
         // check if second entry was evicted from cache
         assert!(cache.get(&1).is_none());
@@ -226,14 +834,225 @@ mod tests {
         assert_eq!(cache.get(&0), Some(Arc::new(0)));
         assert_eq!(cache.get(&2), Some(Arc::new(2)));
     }
+
+    #[test]
+    fn test_cache_shards_divide_capacity() {
+        // 4 shards (already a power of two) sharing a 100 byte budget
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 4);
+        assert_eq!(cache.get_capacity(), 100);
+
+        // shard count is rounded up to the next power of two
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 3);
+        assert_eq!(cache.shards.len(), 4);
+    }
+
+    #[test]
+    fn test_cache_default_shards() {
+        let cache: LruCache<u32, u32> = LruCache::new(800);
+        assert_eq!(cache.shards.len(), DEFAULT_SHARDS);
+    }
+
+    #[test]
+    fn test_cache_clock_read_does_not_reorder() {
+        let cache: LruCache<u32, u32> =
+            LruCache::with_shards_and_policy(200, 1, EvictionPolicy::Clock);
+
+        cache.put(0, 0, 100, Priority::Low);
+        cache.put(1, 1, 100, Priority::Low);
+
+        // reading entry 0 only marks it referenced; it stays at the
+        // front of the queue instead of moving to the back
+        assert_eq!(cache.get(&0), Some(Arc::new(0)));
+
+        // entry 0 is referenced, so it gets a second chance and entry 1
+        // is evicted instead
+        cache.put(2, 2, 100, Priority::Low);
+        assert_eq!(cache.get(&0), Some(Arc::new(0)));
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&2), Some(Arc::new(2)));
+    }
+
+    #[test]
+    fn test_cache_secondary_tier_promotion() {
+        let cache: LruCache<u32, u32> = LruCache::with_secondary_options(
+            100,
+            CacheOptions {
+                shards: 1,
+                secondary_capacity_in_bytes: 100,
+                ..Default::default()
+            },
+        );
+
+        cache.put(0, 0, 100, Priority::Low);
+        // evicts entry 0 from the primary tier into the secondary one
+        cache.put(1, 1, 100, Priority::Low);
+
+        assert_eq!(cache.promotions(), 0);
+
+        // entry 0 is reconstructed from the secondary tier...
+        assert_eq!(cache.get(&0), Some(Arc::new(0)));
+        assert_eq!(cache.promotions(), 1);
+
+        // ...which in turn demotes entry 1 to the secondary tier, where
+        // it can likewise be promoted back
+        assert_eq!(cache.get(&1), Some(Arc::new(1)));
+        assert_eq!(cache.promotions(), 2);
+    }
+
+    #[test]
+    fn test_cache_without_secondary_tier_drops_evicted_entries() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
+
+        cache.put(0, 0, 100, Priority::Low);
+        cache.put(1, 1, 100, Priority::Low);
+
+        // with no secondary tier configured, the evicted entry is gone
+        assert!(cache.get(&0).is_none());
+        assert_eq!(cache.promotions(), 0);
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
+
+        cache.put(0, 0, 100, Priority::Low);
+        cache.put(1, 1, 100, Priority::Low);
+
+        assert_eq!(cache.get(&0), Some(Arc::new(0))); // hit
+        assert!(cache.get(&2).is_none()); // miss
+
+        cache.put(2, 2, 100, Priority::Low); // evicts entry 1
+
+        let stats = cache.stats();
+        assert_eq!(stats.lookups, 2);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.current_size, 200);
+        assert_eq!(stats.peak_size, 200);
+        assert_eq!(stats.capacity, 200);
+
+        cache.reset_stats();
+        let stats = cache.stats();
+        assert_eq!(stats.lookups, 0);
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 0);
+        assert_eq!(stats.evictions, 0);
+        assert_eq!(stats.peak_size, 0);
+        // live state (current_size/capacity) isn't part of the reset counters
+        assert_eq!(stats.current_size, 200);
+        assert_eq!(stats.capacity, 200);
+    }
+
+    #[test]
+    fn test_cache_high_priority_pinning() {
+        // half the 300-byte shard is reserved for high-priority entries
+        let cache: LruCache<u32, u32> = LruCache::with_options(
+            300,
+            CacheOptions {
+                shards: 1,
+                high_priority_ratio: 0.5,
+                ..Default::default()
+            },
+        );
+
+        cache.put(0, 0, 100, Priority::High);
+        cache.put(1, 1, 100, Priority::Low);
+        cache.put(2, 2, 100, Priority::Low);
+
+        // entry 0 is protected (high-priority pool is at 100/150 bytes),
+        // so the low-priority entry 1 is evicted in its place
+        cache.put(3, 3, 100, Priority::Low);
+        assert_eq!(cache.get(&0), Some(Arc::new(0)));
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&2), Some(Arc::new(2)));
+        assert_eq!(cache.get(&3), Some(Arc::new(3)));
+    }
+
+    #[test]
+    fn test_cache_high_priority_pool_overflow_demotes() {
+        // only ~100 bytes reserved for high-priority entries in a 300 byte
+        // shard
+        let cache: LruCache<u32, u32> = LruCache::with_options(
+            300,
+            CacheOptions {
+                shards: 1,
+                high_priority_ratio: 0.34,
+                ..Default::default()
+            },
+        );
+
+        cache.put(0, 0, 100, Priority::High);
+        cache.put(1, 1, 100, Priority::High);
+        cache.put(2, 2, 100, Priority::Low);
+
+        // high-priority pool is already full (200 bytes in a ~102 byte
+        // budget), so the oldest high-priority entry (0) is demoted and
+        // evicted instead of the newer low-priority entry 2
+        cache.put(3, 3, 100, Priority::Low);
+        assert!(cache.get(&0).is_none());
+        assert_eq!(cache.get(&1), Some(Arc::new(1)));
+        assert_eq!(cache.get(&2), Some(Arc::new(2)));
+        assert_eq!(cache.get(&3), Some(Arc::new(3)));
+    }
+
+    #[test]
+    fn test_cache_set_capacity_preserves_high_priority_ratio() {
+        // 150-byte shard, half reserved for high-priority: too small
+        // (75 bytes) to protect a 100-byte entry
+        let cache: LruCache<u32, u32> = LruCache::with_options(
+            150,
+            CacheOptions {
+                shards: 1,
+                high_priority_ratio: 0.5,
+                ..Default::default()
+            },
+        );
+
+        // growing the shard should re-derive the high-priority budget
+        // from the same 0.5 ratio (150 bytes), not leave it at 75
+        cache.set_capacity(300);
+
+        cache.put(0, 0, 100, Priority::High);
+        cache.put(1, 1, 100, Priority::Low);
+        cache.put(2, 2, 100, Priority::Low);
+
+        // entry 0 is now protected (high-priority pool is at 100/150
+        // bytes), so the low-priority entry 1 is evicted in its place
+        cache.put(3, 3, 100, Priority::Low);
+        assert_eq!(cache.get(&0), Some(Arc::new(0)));
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&2), Some(Arc::new(2)));
+        assert_eq!(cache.get(&3), Some(Arc::new(3)));
+    }
+
+    #[test]
+    fn test_cache_put_sized_accounts_for_heap_allocations() {
+        let cache: LruCache<u32, String> = LruCache::with_shards(1_000_000, 1);
+
+        let small = String::from("hi");
+        let big = "x".repeat(10_000);
+
+        cache.put_sized(0, small.clone());
+        let stats = cache.stats();
+        let small_weight = stats.current_size;
+        // at minimum the weight must cover the heap allocation itself
+        assert!(small_weight >= small.capacity());
+
+        cache.put_sized(1, big.clone());
+        let total_weight = cache.stats().current_size;
+        // the much larger string should be weighed proportionally heavier
+        assert!(total_weight - small_weight >= big.capacity());
+    }
 }
 
 /// Wrappers around `LruCache` to provide FFI-bindings for libopenslide.
 pub mod ffi {
-    use super::LruCache;
+    use super::{CacheOptions, CacheStats, Compressible, EvictionPolicy, LruCache, Priority};
     use std::hash::Hash;
     use std::os::raw::{c_int, c_void};
     use std::ptr;
+    use std::slice;
     use std::sync::Arc;
 
     #[allow(non_camel_case_types)]
@@ -243,7 +1062,7 @@ pub mod ffi {
     // signatures more readable. But it isn't really exposed through the FFI
     // API so there isn't much to document.
     #[doc(hidden)]
-    #[derive(Hash, Eq, PartialEq)]
+    #[derive(Clone, Hash, Eq, PartialEq)]
     pub struct CacheKey(*const c_void, i64, i64);
 
     /// A CacheEntry struct that wraps C pointers with a custom drop function.
@@ -276,16 +1095,63 @@ pub mod ffi {
         }
     }
 
+    // Allow a CacheEntry to round-trip through the secondary (compressed)
+    // tier: `to_bytes` copies the C-owned buffer out, and `from_bytes`
+    // reallocates a fresh `g_slice_alloc`'d buffer from it so the entry
+    // can be dropped the same way as any other `CacheEntry`.
+    impl Compressible for CacheEntry {
+        fn to_bytes(&self) -> Vec<u8> {
+            unsafe { slice::from_raw_parts(self.data as *const u8, self.size).to_vec() }
+        }
+
+        fn from_bytes(bytes: Vec<u8>) -> Self {
+            #[link(name = "glib-2.0")]
+            extern "C" {
+                fn g_slice_alloc(size: size_t) -> *mut c_void;
+            }
+            let size = bytes.len();
+            unsafe {
+                let data = g_slice_alloc(size);
+                ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, size);
+                CacheEntry { data, size }
+            }
+        }
+    }
+
     /// Useful cache size to allocate per open slide handle.
     /// currently defaults to 32MB.
     pub const _OPENSLIDE_USEFUL_CACHE_SIZE: size_t = 1024 * 1024 * 32;
 
-    /// Create a new cache.
+    /// Eviction policy codes accepted by `_openslide_cache_create`.
+    pub const _OPENSLIDE_CACHE_POLICY_LRU: c_int = 0;
+    pub const _OPENSLIDE_CACHE_POLICY_CLOCK: c_int = 1;
+
+    /// Priority codes accepted by `_openslide_cache_put`.
+    pub const _OPENSLIDE_CACHE_PRIORITY_LOW: c_int = 0;
+    pub const _OPENSLIDE_CACHE_PRIORITY_HIGH: c_int = 1;
+
+    /// Create a new cache. `secondary_capacity_in_bytes` sizes an optional
+    /// compressed secondary tier that evicted tiles are spilled into
+    /// instead of being dropped outright; pass `0` to disable it.
     #[no_mangle]
     pub extern "C" fn _openslide_cache_create(
         capacity_in_bytes: c_int,
+        policy: c_int,
+        secondary_capacity_in_bytes: c_int,
     ) -> *mut LruCache<CacheKey, CacheEntry> {
-        Box::into_raw(Box::new(LruCache::new(capacity_in_bytes as usize)))
+        let policy = if policy == _OPENSLIDE_CACHE_POLICY_CLOCK {
+            EvictionPolicy::Clock
+        } else {
+            EvictionPolicy::Lru
+        };
+        Box::into_raw(Box::new(LruCache::with_secondary_options(
+            capacity_in_bytes as usize,
+            CacheOptions {
+                policy,
+                secondary_capacity_in_bytes: secondary_capacity_in_bytes as usize,
+                ..Default::default()
+            },
+        )))
     }
 
     /// Destroy a cache and drop all cached objects.
@@ -323,6 +1189,70 @@ pub mod ffi {
         cache.set_capacity(capacity_in_bytes as usize);
     }
 
+    /// Set the fraction (`0.0..=1.0`) of the cache's capacity reserved for
+    /// high-priority entries.
+    #[no_mangle]
+    pub extern "C" fn _openslide_cache_set_high_priority_ratio(
+        cache: *const LruCache<CacheKey, CacheEntry>,
+        ratio: f64,
+    ) {
+        let cache = unsafe {
+            assert!(!cache.is_null());
+            &*cache
+        };
+        cache.set_high_priority_ratio(ratio);
+    }
+
+    /// Hit/miss/eviction counters for a cache, as reported by
+    /// `_openslide_cache_get_stats`.
+    #[repr(C)]
+    pub struct _openslide_cache_stats {
+        pub lookups: c_int,
+        pub hits: c_int,
+        pub misses: c_int,
+        pub evictions: c_int,
+        pub current_size: c_int,
+        pub peak_size: c_int,
+        pub capacity: c_int,
+    }
+
+    /// Fill `stats` with a snapshot of the cache's hit/miss/eviction
+    /// counters, alongside its current and configured size.
+    #[no_mangle]
+    pub extern "C" fn _openslide_cache_get_stats(
+        cache: *const LruCache<CacheKey, CacheEntry>,
+        stats: *mut _openslide_cache_stats,
+    ) {
+        let cache = unsafe {
+            assert!(!cache.is_null());
+            &*cache
+        };
+        assert!(!stats.is_null());
+        let CacheStats {
+            lookups,
+            hits,
+            misses,
+            evictions,
+            current_size,
+            peak_size,
+            capacity,
+        } = cache.stats();
+        unsafe {
+            ptr::write(
+                stats,
+                _openslide_cache_stats {
+                    lookups: lookups as c_int,
+                    hits: hits as c_int,
+                    misses: misses as c_int,
+                    evictions: evictions as c_int,
+                    current_size: current_size as c_int,
+                    peak_size: peak_size as c_int,
+                    capacity: capacity as c_int,
+                },
+            );
+        }
+    }
+
     /// Add an object to the cache.
     ///
     /// Adds an object `data` that is `size_in_bytes` long to the cache in the
@@ -330,6 +1260,10 @@ pub mod ffi {
     /// is already stored in that location as well as the least recently accessed
     /// items that exceed the configured cache size.
     ///
+    /// `priority` is `_OPENSLIDE_CACHE_PRIORITY_LOW` (the default) or
+    /// `_OPENSLIDE_CACHE_PRIORITY_HIGH`, which protects the entry from
+    /// eviction until the high-priority pool itself overflows.
+    ///
     /// This function returns a reference to the cached `entry`, which must be
     /// released with [`_openslide_cache_entry_unref()`].
     ///
@@ -342,6 +1276,7 @@ pub mod ffi {
         y: i64,
         data: *mut c_void,
         size_in_bytes: c_int,
+        priority: c_int,
         entry: *mut *const CacheEntry,
     ) {
         let cache = unsafe {
@@ -351,9 +1286,14 @@ pub mod ffi {
         let size = size_in_bytes as usize;
         let key = CacheKey(plane, x, y);
         let val = CacheEntry { data, size };
+        let priority = if priority == _OPENSLIDE_CACHE_PRIORITY_HIGH {
+            Priority::High
+        } else {
+            Priority::Low
+        };
 
         // put a copy in the cache, get back a referenced copy
-        let arc = cache.put(key, val, size);
+        let arc = cache.put(key, val, size, priority);
 
         // and return a reference to the caller
         if !entry.is_null() {
@@ -424,7 +1364,7 @@ pub mod ffi {
             let null_ptr: *mut *const CacheEntry = std::ptr::null_mut();
             let null = std::ptr::null_mut();
 
-            let cache = _openslide_cache_create(200 * 1024 * 1024);
+            let cache = _openslide_cache_create(200 * 1024 * 1024, _OPENSLIDE_CACHE_POLICY_LRU, 0);
 
             // check key that was never inserted in cache
             assert_eq!(_openslide_cache_get(cache, null, 0, 0, entry_ptr), null);
@@ -440,7 +1380,16 @@ pub mod ffi {
                     }
                     let size = 100 * 1024 * 1024;
                     let data = g_slice_alloc(size);
-                    _openslide_cache_put(cache, null, i, 0, data, size as c_int, null_ptr);
+                    _openslide_cache_put(
+                        cache,
+                        null,
+                        i,
+                        0,
+                        data,
+                        size as c_int,
+                        _OPENSLIDE_CACHE_PRIORITY_LOW,
+                        null_ptr,
+                    );
                 }
             }
 
@@ -23,10 +23,23 @@
 //! This is an implementation of a LRU Cache that evicts objects
 //! based on the total size of the cached objects.
 //!
+//! Internally the cache is split into `n` independently-locked shards
+//! (`LruCache::with_shards`) so concurrent `get`/`put` calls from
+//! different decoding threads don't serialize behind a single lock.
+//!
+//! This is a deliberately separate, standalone `LruCache` from the one in
+//! `cache.rs`: `cache.rs` is the one wired into libopenslide's per-handle
+//! tile cache (CLOCK eviction, a compressed secondary tier, priority
+//! pools), while this one backs the simpler, ref-counted shared-cache FFI
+//! surface in `ffi` below (one bounded cache attached to several
+//! `openslide_t`s). Not a fork of the same facility; pick whichever fits
+//! the consumer.
+//!
 //! # Examples
 //!
 //! ```
-//! let cache: LruCache<u32, u32> = LruCache::new(200);
+//! // pin to a single shard so eviction order is deterministic
+//! let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
 //!
 //! cache.put(0, 0, 100);
 //! cache.put(1, 1, 100);
@@ -47,30 +60,76 @@
 
 extern crate linked_hash_map;
 use linked_hash_map::LinkedHashMap;
-use std::hash::Hash;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
 use std::sync::{Arc, Mutex};
 
+/// Types that know their own memory footprint, used by
+/// `LruCache::put_weighed` to derive a cache entry's weight instead of
+/// requiring the caller to hand-compute `size`.
+pub trait Weight {
+    fn weight(&self) -> usize;
+}
+
+impl Weight for Vec<u8> {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A decoded RGBA tile buffer, as produced by whole-slide tile readers.
+pub struct RgbaTile {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Weight for RgbaTile {
+    fn weight(&self) -> usize {
+        std::mem::size_of::<Self>() + self.width * self.height * 4
+    }
+}
+
 struct CacheItem<V> {
     entry: Arc<V>,
     size: usize,
 }
 
-struct _LruCache<K, V> {
-    lru: LinkedHashMap<K, CacheItem<V>>,
+/// Per-shard hit/miss/eviction counters, plus the peak `total_size` the
+/// shard has ever held. Combined across shards by `LruCache::stats`.
+#[derive(Default, Clone, Copy)]
+struct Counters {
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+    peak_size: usize,
+}
+
+struct _LruCache<K, V, S> {
+    lru: LinkedHashMap<K, CacheItem<V>, S>,
     capacity: usize,
     total_size: usize,
+    counters: Counters,
 }
 
-impl<K, V> _LruCache<K, V>
+impl<K, V, S> _LruCache<K, V, S>
 where
     K: Hash + Eq,
+    S: BuildHasher,
 {
+    fn _note_grew(&mut self) {
+        if self.total_size > self.counters.peak_size {
+            self.counters.peak_size = self.total_size;
+        }
+    }
+
     fn _shrink_to_fit(&mut self, reserve: usize) {
         // drop entries to clear cache space
         while self.total_size + reserve > self.capacity {
             match self.lru.pop_front() {
                 Some(val) => {
                     self.total_size -= val.1.size;
+                    self.counters.evictions += 1;
                 }
                 None => break,
             }
@@ -78,35 +137,183 @@ where
     }
 }
 
-pub struct LruCache<K, V>(Mutex<_LruCache<K, V>>);
+/// A point-in-time snapshot of cache effectiveness, summed across all
+/// shards, returned by `LruCache::stats`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    pub current_bytes: usize,
+    pub peak_bytes: usize,
+    pub capacity: usize,
+}
+
+/// Default number of shards used by `LruCache::new`.
+const DEFAULT_SHARDS: usize = 8;
+
+/// LRU cache implementation.
+///
+/// The cache is partitioned into a number of shards, each guarded by its
+/// own mutex, so that lookups that hash to different shards don't block
+/// each other.
+///
+/// `S` is the `BuildHasher` used both to pick a key's shard and by that
+/// shard's underlying map; it defaults to the standard library's
+/// DoS-resistant `RandomState`. Use `with_hasher` to plug in a faster
+/// non-cryptographic hasher for trusted, dense key domains like numeric
+/// tile coordinates.
+pub struct LruCache<K, V, S = RandomState> {
+    shards: Vec<Mutex<_LruCache<K, V, S>>>,
+    hash_builder: S,
+}
 
-impl<K, V> LruCache<K, V>
+impl<K, V> LruCache<K, V, RandomState>
 where
     K: Hash + Eq,
 {
     /// Initialize a new LruCache, with the specified maximum size.
-    pub fn new(capacity_in_bytes: usize) -> LruCache<K, V> {
-        LruCache(Mutex::new(_LruCache {
-            lru: LinkedHashMap::new(),
-            capacity: capacity_in_bytes,
-            total_size: 0,
-        }))
+    ///
+    /// Uses `DEFAULT_SHARDS` shards; see `with_shards`/`with_hasher` to
+    /// customize.
+    pub fn new(capacity_in_bytes: usize) -> LruCache<K, V, RandomState> {
+        LruCache::with_shards(capacity_in_bytes, DEFAULT_SHARDS)
+    }
+
+    /// Initialize a new LruCache with an explicit number of shards.
+    ///
+    /// `capacity_in_bytes` is divided evenly across the `shards` shards,
+    /// so each shard independently evicts once it holds
+    /// `capacity_in_bytes / shards` bytes. `shards` is clamped to at
+    /// least 1.
+    pub fn with_shards(capacity_in_bytes: usize, shards: usize) -> LruCache<K, V, RandomState> {
+        LruCache::with_shards_and_hasher(capacity_in_bytes, shards, RandomState::default())
+    }
+}
+
+impl<K, V, S> LruCache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Clone,
+{
+    /// Initialize a new LruCache using a specific `BuildHasher` instead of
+    /// the default `RandomState`.
+    ///
+    /// `RandomState` resists hash-flood DoS from attacker-controlled
+    /// keys; for trusted, dense numeric keys like tile coordinates, an
+    /// FNV/ahash-style hasher here is faster.
+    pub fn with_hasher(capacity_in_bytes: usize, hasher: S) -> LruCache<K, V, S> {
+        LruCache::with_shards_and_hasher(capacity_in_bytes, DEFAULT_SHARDS, hasher)
+    }
+
+    // Shared by `with_shards` (with the default `RandomState`) and
+    // `with_hasher` (with a caller-supplied `S`).
+    fn with_shards_and_hasher(
+        capacity_in_bytes: usize,
+        shards: usize,
+        hasher: S,
+    ) -> LruCache<K, V, S> {
+        let num_shards = shards.max(1);
+        let per_shard_capacity = capacity_in_bytes / num_shards;
+
+        let shards = (0..num_shards)
+            .map(|_| {
+                Mutex::new(_LruCache {
+                    lru: LinkedHashMap::with_hasher(hasher.clone()),
+                    capacity: per_shard_capacity,
+                    total_size: 0,
+                    counters: Counters::default(),
+                })
+            })
+            .collect();
+
+        LruCache {
+            shards,
+            hash_builder: hasher,
+        }
+    }
+
+    // Pick the shard responsible for `key`.
+    fn shard_for(&self, key: &K) -> &Mutex<_LruCache<K, V, S>> {
+        let index = (self.hash_builder.hash_one(key) as usize) % self.shards.len();
+        &self.shards[index]
     }
 
     /// Get configured LruCache maximum size
     ///
-    /// **Note to self:** Maybe it would be more useful to return
-    /// the total size of currently cached objects?
+    /// This is the sum of the (evenly divided) per-shard capacities,
+    /// which may be a few bytes below the originally requested size due
+    /// to rounding. See `current_size` for how many of those bytes are
+    /// actually in use.
     pub fn get_capacity(&self) -> usize {
-        let cache = self.0.lock().unwrap();
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().capacity)
+            .sum()
+    }
+
+    /// Total size, in bytes, of the objects currently resident in the
+    /// cache, summed across all shards.
+    pub fn current_size(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().total_size)
+            .sum()
+    }
+
+    /// Total number of entries currently cached, summed across all
+    /// shards.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().lru.len())
+            .sum()
+    }
 
-        cache.capacity
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove every entry from the cache.
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut cache = shard.lock().unwrap();
+            cache.lru.clear();
+            cache.total_size = 0;
+        }
+    }
+
+    /// Snapshot hit/miss/eviction counters and size accounting, summed
+    /// across all shards.
+    pub fn stats(&self) -> CacheStats {
+        self.shards.iter().fold(CacheStats::default(), |acc, shard| {
+            let cache = shard.lock().unwrap();
+            CacheStats {
+                hits: acc.hits + cache.counters.hits,
+                misses: acc.misses + cache.counters.misses,
+                evictions: acc.evictions + cache.counters.evictions,
+                current_bytes: acc.current_bytes + cache.total_size,
+                peak_bytes: acc.peak_bytes + cache.counters.peak_size,
+                capacity: acc.capacity + cache.capacity,
+            }
+        })
+    }
+
+    /// Reset the hit/miss/eviction/peak-size counters to zero without
+    /// disturbing the cached entries themselves.
+    pub fn reset_stats(&self) {
+        for shard in &self.shards {
+            let mut cache = shard.lock().unwrap();
+            cache.counters = Counters::default();
+        }
     }
 
     /// Set new LruCache maximum capacity
     ///
-    /// Will discard least recently used objects that exceed the new
-    /// size, can as such be used to empty the current cache.
+    /// The new capacity is divided evenly across the existing shards,
+    /// each of which discards its least recently used objects that
+    /// exceed its new share; setting capacity to 0 empties the cache.
     ///
     /// ```
     /// let saved = cache.get_capacity();
@@ -114,21 +321,24 @@ where
     /// cache.set_capacity(saved);
     /// ```
     pub fn set_capacity(&self, capacity_in_bytes: usize) {
-        let mut cache = self.0.lock().unwrap();
-
-        cache.capacity = capacity_in_bytes;
-        cache._shrink_to_fit(0); // resize cache to fit new size
+        let per_shard_capacity = capacity_in_bytes / self.shards.len();
+        for shard in &self.shards {
+            let mut cache = shard.lock().unwrap();
+            cache.capacity = per_shard_capacity;
+            cache._shrink_to_fit(0); // resize shard to fit new size
+        }
     }
 
     /// Add a new object to the cache.
     ///
     /// If the key already exists the existing entry is replaced.
-    /// Otherwise if the cache is full the least-recently-used
-    /// cached objects are discarded before the new object is added.
+    /// Otherwise if the key's shard is full the least-recently-used
+    /// cached objects in that shard are discarded before the new object
+    /// is added.
     ///
     /// This function returns a reference to the newly added object.
     pub fn put(&self, key: K, val: V, size: usize) -> Arc<V> {
-        let mut cache = self.0.lock().unwrap();
+        let mut cache = self.shard_for(&key).lock().unwrap();
 
         // remove key if it exists
         if let Some(old_val) = cache.lru.remove(&key) {
@@ -148,28 +358,102 @@ where
             },
         );
         cache.total_size += size;
+        cache._note_grew();
         val
     }
 
+    /// Add a new object to the cache, deriving its weight from
+    /// `Weight::weight` instead of requiring the caller to hand-compute
+    /// `size`. Otherwise behaves exactly like `put`.
+    pub fn put_weighed(&self, key: K, val: V) -> Arc<V>
+    where
+        V: Weight,
+    {
+        let size = val.weight();
+        self.put(key, val, size)
+    }
+
     /// Retrieve a cached object.
     ///
     /// If the key does not exist this function returns None.
     /// Otherwise it returns a reference to the cached object.
     pub fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut cache = self.0.lock().unwrap();
+        let mut cache = self.shard_for(key).lock().unwrap();
 
-        let val = cache.lru.get_refresh(key)?;
+        match cache.lru.get_refresh(key) {
+            Some(val) => {
+                let entry = val.entry.clone();
+                cache.counters.hits += 1;
+                Some(entry)
+            }
+            None => {
+                cache.counters.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Read a cached object without refreshing its LRU position.
+    ///
+    /// Unlike `get`, this doesn't move the entry to the back of its
+    /// shard's queue, so it's safe to use for prefetch logic that probes
+    /// the cache without wanting to disturb eviction order.
+    pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+        let cache = self.shard_for(key).lock().unwrap();
+
+        let val = cache.lru.get(key)?;
         Some(val.entry.clone())
     }
+
+    /// Remove and return a cached object, if present.
+    pub fn pop(&self, key: &K) -> Option<Arc<V>> {
+        let mut cache = self.shard_for(key).lock().unwrap();
+
+        let val = cache.lru.remove(key)?;
+        cache.total_size -= val.size;
+        Some(val.entry)
+    }
+}
+
+// A fixed-key hasher (std's `DefaultHasher` with no random seed), standing
+// in for a real FNV/ahash dependency, just to exercise
+// `LruCache::with_hasher` with something other than `RandomState`.
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct FnvBuildHasher;
+
+#[cfg(test)]
+impl BuildHasher for FnvBuildHasher {
+    type Hasher = std::collections::hash_map::DefaultHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        std::collections::hash_map::DefaultHasher::new()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cache_with_custom_hasher() {
+        // generous per-shard headroom so all 10 entries survive even in
+        // the worst case where they all hash to the same shard
+        let cache: LruCache<u32, u32, FnvBuildHasher> =
+            LruCache::with_hasher(16_000, FnvBuildHasher);
+
+        for i in 0..10 {
+            cache.put(i, i * 10, 100);
+        }
+        for i in 0..10 {
+            assert_eq!(cache.get(&i), Some(Arc::new(i * 10)));
+        }
+    }
+
     #[test]
     fn test_cache_capacity() {
-        let cache: LruCache<u32, u32> = LruCache::new(100);
+        // single shard so the full capacity evicts deterministically
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
         assert_eq!(cache.get_capacity(), 100);
 
         cache.put(0, 0, 100);
@@ -183,7 +467,7 @@ mod tests {
 
     #[test]
     fn test_cache_empty() {
-        let cache: LruCache<u32, u32> = LruCache::new(100);
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
 
         // check key that was never inserted in cache
         assert!(cache.get(&0).is_none());
@@ -191,7 +475,7 @@ mod tests {
 
     #[test]
     fn test_cache_eviction() {
-        let cache: LruCache<u32, u32> = LruCache::new(100);
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 1);
 
         cache.put(0, 0, 100);
         cache.put(1, 1, 100);
@@ -205,7 +489,7 @@ mod tests {
 
     #[test]
     fn test_cache_lru() {
-        let cache: LruCache<u32, u32> = LruCache::new(200);
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
 
         cache.put(0, 0, 100);
         cache.put(1, 1, 100);
@@ -223,4 +507,376 @@ mod tests {
         assert_eq!(cache.get(&0), Some(Arc::new(0)));
         assert_eq!(cache.get(&2), Some(Arc::new(2)));
     }
+
+    #[test]
+    fn test_cache_shards_divide_capacity() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(100, 4);
+        assert_eq!(cache.get_capacity(), 100);
+        assert_eq!(cache.shards.len(), 4);
+    }
+
+    #[test]
+    fn test_cache_default_shards() {
+        let cache: LruCache<u32, u32> = LruCache::new(800);
+        assert_eq!(cache.shards.len(), DEFAULT_SHARDS);
+    }
+
+    #[test]
+    fn test_cache_put_weighed_vec() {
+        let cache: LruCache<u32, Vec<u8>> = LruCache::with_shards(10, 1);
+
+        cache.put_weighed(0, vec![0u8; 6]);
+        cache.put_weighed(1, vec![0u8; 6]);
+
+        // the first entry's weight (6 bytes) was charged against
+        // total_size, so it got evicted to make room for the second
+        assert!(cache.get(&0).is_none());
+        assert_eq!(cache.get(&1).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_cache_put_weighed_rgba_tile() {
+        // just over one tile's worth of weight (16*16*4 pixel bytes, plus
+        // struct overhead), so a second tile evicts the first
+        let tile_bytes = 16 * 16 * 4;
+        let cache: LruCache<u32, RgbaTile> =
+            LruCache::with_shards(tile_bytes + std::mem::size_of::<RgbaTile>() + 8, 1);
+
+        let make_tile = || RgbaTile {
+            width: 16,
+            height: 16,
+            pixels: vec![0u8; tile_bytes],
+        };
+
+        cache.put_weighed(0, make_tile());
+        cache.put_weighed(1, make_tile());
+
+        assert!(cache.get(&0).is_none());
+        assert!(cache.get(&1).is_some());
+    }
+
+    #[test]
+    fn test_cache_len_and_current_size() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
+        assert_eq!(cache.len(), 0);
+        assert!(cache.is_empty());
+        assert_eq!(cache.current_size(), 0);
+
+        cache.put(0, 0, 100);
+        cache.put(1, 1, 100);
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.is_empty());
+        assert_eq!(cache.current_size(), 200);
+    }
+
+    #[test]
+    fn test_cache_clear() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
+
+        cache.put(0, 0, 100);
+        cache.put(1, 1, 100);
+        cache.clear();
+
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.current_size(), 0);
+        assert!(cache.get(&0).is_none());
+        assert!(cache.get(&1).is_none());
+    }
+
+    #[test]
+    fn test_cache_peek_does_not_refresh() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
+
+        cache.put(0, 0, 100);
+        cache.put(1, 1, 100);
+
+        // peeking entry 0 must not move it to the back of the queue
+        assert_eq!(cache.peek(&0), Some(Arc::new(0)));
+
+        // so this still evicts entry 0, not entry 1
+        cache.put(2, 2, 100);
+        assert!(cache.get(&0).is_none());
+        assert_eq!(cache.get(&1), Some(Arc::new(1)));
+        assert_eq!(cache.get(&2), Some(Arc::new(2)));
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
+
+        cache.put(0, 0, 100);
+        cache.put(1, 1, 100);
+        cache.get(&0); // hit
+        cache.get(&42); // miss
+        cache.put(2, 2, 100); // evicts entry 1
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.current_bytes, 200);
+        assert_eq!(stats.peak_bytes, 200);
+        assert_eq!(stats.capacity, 200);
+
+        cache.reset_stats();
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 0);
+        assert_eq!(stats.evictions, 0);
+        // resetting counters doesn't evict or forget cached entries,
+        // though peak_bytes (itself a counter) starts tracking again
+        assert_eq!(stats.current_bytes, 200);
+        assert_eq!(stats.peak_bytes, 0);
+    }
+
+    #[test]
+    fn test_cache_pop() {
+        let cache: LruCache<u32, u32> = LruCache::with_shards(200, 1);
+
+        cache.put(0, 0, 100);
+        cache.put(1, 1, 100);
+
+        assert_eq!(cache.pop(&0), Some(Arc::new(0)));
+        assert_eq!(cache.current_size(), 100);
+        assert_eq!(cache.len(), 1);
+
+        // gone for good, not just evicted
+        assert!(cache.get(&0).is_none());
+        assert!(cache.pop(&0).is_none());
+    }
+}
+
+/// Wrappers around `LruCache` to provide FFI-bindings for libopenslide.
+///
+/// Unlike `cache::ffi`, the handle returned by `_openslide_shared_cache_create`
+/// is reference counted, so it can be attached to more than one
+/// `openslide_t`: every simultaneously-open slide shares the same bounded
+/// cache instead of each
+/// holding its own unbounded buffers. Its exported symbols are prefixed
+/// `_openslide_shared_cache_` (rather than `_openslide_cache_`) so they
+/// don't collide with `cache::ffi`'s own, differently-shaped FFI surface.
+pub mod ffi {
+    use super::LruCache;
+    use std::ptr;
+    use std::slice;
+    use std::sync::Arc;
+
+    /// Opaque, reference-counted tile cache handle.
+    pub type OpenslideCache = LruCache<Vec<u8>, Vec<u8>>;
+
+    /// Create a new, empty shared tile cache with the given capacity in
+    /// bytes.
+    ///
+    /// The returned handle is reference counted (see
+    /// `_openslide_shared_cache_ref`), so the underlying cache and its
+    /// contents are only freed once every reference has been released
+    /// with `_openslide_shared_cache_destroy`.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_create(
+        capacity_in_bytes: i64,
+    ) -> *const OpenslideCache {
+        Arc::into_raw(Arc::new(LruCache::new(capacity_in_bytes.max(0) as usize)))
+    }
+
+    /// Take another reference on a shared cache, e.g. to attach it to an
+    /// additional `openslide_t`. Returns the same pointer, now backed by
+    /// one more reference.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_ref(
+        cache: *const OpenslideCache,
+    ) -> *const OpenslideCache {
+        assert!(!cache.is_null());
+        unsafe {
+            Arc::increment_strong_count(cache);
+        }
+        cache
+    }
+
+    /// Release a reference to a shared cache taken by
+    /// `_openslide_shared_cache_create` or `_openslide_shared_cache_ref`.
+    /// Once the last reference is released, the cache and all of its
+    /// contents are freed.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_destroy(cache: *const OpenslideCache) {
+        if !cache.is_null() {
+            unsafe {
+                drop(Arc::from_raw(cache));
+            };
+        }
+    }
+
+    /// Get the currently configured maximum cache size, in bytes.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_get_capacity(cache: *const OpenslideCache) -> i64 {
+        let cache = unsafe {
+            assert!(!cache.is_null());
+            &*cache
+        };
+        cache.get_capacity() as i64
+    }
+
+    /// Set the maximum cache size, in bytes.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_set_capacity(
+        cache: *const OpenslideCache,
+        capacity_in_bytes: i64,
+    ) {
+        let cache = unsafe {
+            assert!(!cache.is_null());
+            &*cache
+        };
+        cache.set_capacity(capacity_in_bytes.max(0) as usize);
+    }
+
+    /// Add `data_len` bytes of `data` to the cache under the byte string
+    /// `key_ptr[..key_len]`.
+    ///
+    /// This function returns a reference to the cached `entry`, which
+    /// must be released with `_openslide_shared_cache_entry_unref()`.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_put(
+        cache: *const OpenslideCache,
+        key_ptr: *const u8,
+        key_len: i64,
+        data: *const u8,
+        data_len: i64,
+        entry: *mut *const Vec<u8>,
+    ) {
+        let cache = unsafe {
+            assert!(!cache.is_null());
+            &*cache
+        };
+        let key = unsafe { slice::from_raw_parts(key_ptr, key_len as usize).to_vec() };
+        let val = unsafe { slice::from_raw_parts(data, data_len as usize).to_vec() };
+        let size = val.len();
+
+        let arc = cache.put(key, val, size);
+
+        if !entry.is_null() {
+            unsafe {
+                ptr::write(entry, Arc::into_raw(arc));
+            }
+        }
+    }
+
+    /// Find a cached byte run for the key `key_ptr[..key_len]`.
+    ///
+    /// Returns a borrowed pointer to the cached bytes, with their exact
+    /// length written to `out_len`, plus a reference to the cached
+    /// `entry`, which must be released with
+    /// `_openslide_shared_cache_entry_unref()`. Both are null on a miss.
+    ///
+    /// The returned pointer stays valid until the matching
+    /// `_openslide_shared_cache_entry_unref()` call.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_get(
+        cache: *const OpenslideCache,
+        key_ptr: *const u8,
+        key_len: i64,
+        out_len: *mut i64,
+        entry: *mut *const Vec<u8>,
+    ) -> *const u8 {
+        let cache = unsafe {
+            assert!(!cache.is_null());
+            &*cache
+        };
+        let key = unsafe { slice::from_raw_parts(key_ptr, key_len as usize).to_vec() };
+
+        match cache.get(&key) {
+            Some(val) => {
+                let data = val.as_ptr();
+                unsafe {
+                    assert!(!out_len.is_null());
+                    assert!(!entry.is_null());
+                    ptr::write(out_len, val.len() as i64);
+                    ptr::write(entry, Arc::into_raw(val));
+                }
+                data
+            }
+            None => unsafe {
+                assert!(!out_len.is_null());
+                assert!(!entry.is_null());
+                ptr::write(out_len, 0);
+                ptr::write(entry, ptr::null());
+                ptr::null()
+            },
+        }
+    }
+
+    /// Release a reference to a cached entry.
+    #[no_mangle]
+    pub extern "C" fn _openslide_shared_cache_entry_unref(entry: *mut Vec<u8>) {
+        if !entry.is_null() {
+            unsafe {
+                drop(Arc::from_raw(entry));
+            };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_cache_shared_across_handles() {
+            // a multiple of DEFAULT_SHARDS so get_capacity() round-trips exactly
+            let cache = _openslide_shared_cache_create(160);
+            // a second slide attaching to the same cache
+            let cache2 = _openslide_shared_cache_ref(cache);
+
+            let key = b"plane\x000\x000";
+            let data = b"decoded tile bytes";
+
+            let mut put_entry: *const Vec<u8> = ptr::null();
+            _openslide_shared_cache_put(
+                cache,
+                key.as_ptr(),
+                key.len() as i64,
+                data.as_ptr(),
+                data.len() as i64,
+                &mut put_entry,
+            );
+            assert!(!put_entry.is_null());
+            _openslide_shared_cache_entry_unref(put_entry as *mut Vec<u8>);
+
+            // look it up through the second handle
+            let mut out_len: i64 = 0;
+            let mut get_entry: *const Vec<u8> = ptr::null();
+            let found = _openslide_shared_cache_get(
+                cache2,
+                key.as_ptr(),
+                key.len() as i64,
+                &mut out_len,
+                &mut get_entry,
+            );
+            assert!(!found.is_null());
+            assert_eq!(out_len, data.len() as i64);
+            let bytes = unsafe { slice::from_raw_parts(found, out_len as usize) };
+            assert_eq!(bytes, data);
+            _openslide_shared_cache_entry_unref(get_entry as *mut Vec<u8>);
+
+            // a miss returns null and a zero length
+            let miss_key = b"nope";
+            let mut miss_len: i64 = -1;
+            let mut miss_entry: *const Vec<u8> = ptr::null();
+            let miss = _openslide_shared_cache_get(
+                cache2,
+                miss_key.as_ptr(),
+                miss_key.len() as i64,
+                &mut miss_len,
+                &mut miss_entry,
+            );
+            assert!(miss.is_null());
+            assert_eq!(miss_len, 0);
+            assert!(miss_entry.is_null());
+
+            // releasing one handle doesn't invalidate the cache for the
+            // other, shared handle
+            _openslide_shared_cache_destroy(cache);
+            assert_eq!(_openslide_shared_cache_get_capacity(cache2), 160);
+
+            _openslide_shared_cache_destroy(cache2);
+        }
+    }
 }